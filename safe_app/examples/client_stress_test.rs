@@ -29,10 +29,74 @@ use safe_app::{Client, CoreError, CoreFuture, FutureExt, PubImmutableData};
 use safe_authenticator::{AuthClient, Authenticator};
 use safe_core::utils;
 use safe_core::{btree_map, ok};
-use safe_nd::{ClientFullId, IData, PublicKey, SeqMutableData, XorName};
-use std::sync::mpsc;
+use safe_nd::{
+    ClientFullId, Error as SndError, IData, MDataSeqEntryActions, PublicKey, SeqMutableData,
+    XorName,
+};
+use sn_data_types::{Money, Sequence, TransferAgreementProof};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use unwrap::unwrap;
 
+/// Aggregate timing and cost stats collected over the course of a run.
+#[derive(Default)]
+struct Timings {
+    latencies: Vec<Duration>,
+    costs: Vec<Money>,
+}
+
+impl Timings {
+    fn record(&mut self, latency: Duration, cost: Option<Money>) {
+        self.latencies.push(latency);
+        if let Some(cost) = cost {
+            self.costs.push(cost);
+        }
+    }
+
+    fn print_summary(&self, total_elapsed: Duration, final_balance: Option<Money>) {
+        if self.latencies.is_empty() {
+            return;
+        }
+
+        let count = self.latencies.len();
+        let total_nanos: u128 = self.latencies.iter().map(Duration::as_nanos).sum();
+        let mean_nanos = total_nanos / count as u128;
+        let min = unwrap!(self.latencies.iter().min());
+        let max = unwrap!(self.latencies.iter().max());
+        let items_per_sec = count as f64 / total_elapsed.as_secs_f64();
+
+        println!("\n\tTiming summary");
+        println!("\t================");
+        println!("Items processed: {}", count);
+        println!("Total time: {:.2}s", total_elapsed.as_secs_f64());
+        println!("Throughput: {:.2} items/sec", items_per_sec);
+        println!(
+            "Latency (min/mean/max): {:?} / {:?} / {:?}",
+            min,
+            Duration::from_nanos(mean_nanos as u64),
+            max
+        );
+
+        if !self.costs.is_empty() {
+            let total_spend = self.costs.iter().fold(Money::zero(), |acc, cost| {
+                acc.checked_add(*cost).unwrap_or(acc)
+            });
+            let average_cost = total_spend / self.costs.len() as u64;
+
+            println!("\n\tCost summary");
+            println!("\t================");
+            println!("Priced items: {}", self.costs.len());
+            println!("Total spend: {}", total_spend);
+            println!("Average cost per priced item: {}", average_cost);
+            if let Some(balance) = final_balance {
+                println!("Remaining balance: {}", balance);
+            }
+        }
+    }
+}
+
 fn random_mutable_data<R: Rng>(
     type_tag: u64,
     public_key: &PublicKey,
@@ -48,8 +112,107 @@ fn random_mutable_data<R: Rng>(
 }
 
 enum Data {
-    Mutable(SeqMutableData),
+    Mutable {
+        data: SeqMutableData,
+        inserts: MDataSeqEntryActions,
+        follow_up: MDataSeqEntryActions,
+        expected_entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        deleted_keys: Vec<Vec<u8>>,
+    },
     Immutable(IData),
+    Sequences {
+        public: Sequence,
+        private: Sequence,
+        expected_entries: Vec<Vec<u8>>,
+    },
+}
+
+/// Build two sequential mutate_mdata batches to run against a freshly Put, empty
+/// `SeqMutableData`: an initial batch that inserts a fresh key per mutation, and a follow-up
+/// batch that updates some of those keys and deletes others. `MDataSeqEntryActions` can only
+/// carry one action per key, so "insert then update/delete" has to be two separate, sequential
+/// mutate_seq_mdata_entries calls rather than one batch chaining both actions on the same key.
+/// Also returns the entry map the follow-up batch is expected to leave behind, and the keys
+/// expected to be gone afterwards.
+fn random_mdata_mutations<R: Rng>(
+    mutations_per_mdata: usize,
+    rng: &mut R,
+) -> (
+    MDataSeqEntryActions,
+    MDataSeqEntryActions,
+    BTreeMap<Vec<u8>, Vec<u8>>,
+    Vec<Vec<u8>>,
+) {
+    let mut inserts = MDataSeqEntryActions::new();
+    let mut follow_up = MDataSeqEntryActions::new();
+    let mut expected_entries = BTreeMap::new();
+    let mut deleted_keys = Vec::new();
+
+    for n in 0..mutations_per_mdata {
+        let key = format!("key-{}", n).into_bytes();
+        let value = utils::generate_random_vector_rng(rng, 16);
+
+        inserts = inserts.ins(key.clone(), value.clone(), 0);
+
+        match n % 3 {
+            // Leave the inserted entry as-is.
+            0 => {
+                let _ = expected_entries.insert(key, value);
+            }
+            // Update it to a different value.
+            1 => {
+                let updated_value = utils::generate_random_vector_rng(rng, 16);
+                follow_up = follow_up.update(key.clone(), updated_value.clone(), 1);
+                let _ = expected_entries.insert(key, updated_value);
+            }
+            // Delete it - it shouldn't show up in the final state.
+            _ => {
+                follow_up = follow_up.del(key.clone(), 1);
+                deleted_keys.push(key);
+            }
+        }
+    }
+
+    (inserts, follow_up, expected_entries, deleted_keys)
+}
+
+/// Build a public/private pair of append-only sequences owned by the logged-in client, and
+/// append `entries_per_sequence` randomly generated entries to each.
+fn random_sequences<R: Rng>(
+    entries_per_sequence: usize,
+    public_key: &PublicKey,
+    rng: &mut R,
+) -> Data {
+    let authority = *public_key;
+
+    let mut public = Sequence::new_public(
+        authority,
+        authority.to_string(),
+        XorName(rng.gen()),
+        200_000,
+        None,
+    );
+    let mut private = Sequence::new_private(
+        authority,
+        authority.to_string(),
+        XorName(rng.gen()),
+        200_001,
+        None,
+    );
+
+    let mut expected_entries = Vec::with_capacity(entries_per_sequence);
+    for _ in 0..entries_per_sequence {
+        let entry = utils::generate_random_vector_rng(rng, 32);
+        unwrap!(public.append(entry.clone()));
+        unwrap!(private.append(entry.clone()));
+        expected_entries.push(entry);
+    }
+
+    Data::Sequences {
+        public,
+        private,
+        expected_entries,
+    }
 }
 
 fn main() {
@@ -88,6 +251,47 @@ fn main() {
                 .requires("seed")
                 .help("Only Get the data, don't Put it. Logs in to an existing account."),
         )
+        .arg(
+            Arg::with_name("sequence")
+                .short("s")
+                .long("sequence")
+                .takes_value(true)
+                .default_value("0")
+                .help("Number of public/private Sequence pairs to Put and Get."),
+        )
+        .arg(
+            Arg::with_name("sequence-entries")
+                .long("sequence-entries")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of entries to append to each Sequence."),
+        )
+        .arg(
+            Arg::with_name("mutations-per-mdata")
+                .long("mutations-per-mdata")
+                .takes_value(true)
+                .default_value("5")
+                .help(
+                    "Number of entries to insert into each MutableData chunk after it's Put; \
+                     a follow-up mutate_mdata batch then updates or deletes some of them.",
+                ),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .short("c")
+                .long("concurrency")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of data items whose PUT/GET round trips are kept in flight simultaneously."),
+        )
+        .arg(
+            Arg::with_name("fail-on-insufficient-balance")
+                .long("fail-on-insufficient-balance")
+                .help(
+                    "Stop with a clean error message as soon as the account runs out of \
+                     balance, instead of logging the error and going on with the remaining items.",
+                ),
+        )
         .arg(
             Arg::with_name("locator")
                 .short("l")
@@ -108,6 +312,11 @@ fn main() {
 
     let immutable_data_count = unwrap!(value_t!(matches, "immutable", usize));
     let mutable_data_count = unwrap!(value_t!(matches, "mutable", usize));
+    let sequence_count = unwrap!(value_t!(matches, "sequence", usize));
+    let sequence_entries = unwrap!(value_t!(matches, "sequence-entries", usize));
+    let mutations_per_mdata = unwrap!(value_t!(matches, "mutations-per-mdata", usize));
+    let concurrency = unwrap!(value_t!(matches, "concurrency", usize)).max(1);
+    let fail_on_insufficient_balance = matches.is_present("fail-on-insufficient-balance");
 
     let seed = if matches.is_present("seed") {
         unwrap!(value_t!(matches, "seed", u64))
@@ -163,7 +372,8 @@ fn main() {
     println!("\nLogged in successfully!");
     println!("Seed: {}", seed);
 
-    let mut stored_data = Vec::with_capacity(mutable_data_count + immutable_data_count);
+    let mut stored_data =
+        Vec::with_capacity(mutable_data_count + immutable_data_count + sequence_count);
 
     for _ in 0..immutable_data_count {
         // Construct data
@@ -184,28 +394,105 @@ fn main() {
 
     let public_key = unwrap!(rx.recv());
 
+    // Replay any transfer-actor events persisted by a previous (e.g. crashed or killed) run of
+    // this same account, so a restarted stress run reconciles locally-applied-but-unconfirmed
+    // debits instead of losing track of them. `resume_transfer_actor` is `async`, so hand it to
+    // `tokio::spawn` - this schedules it onto the runtime already driving `client`'s other async
+    // work under `auth.send`, rather than spinning up a second, competing one - and block this
+    // (synchronous) thread on the result the same way every other query in this file is bridged
+    // back from `auth.send`.
+    let (tx, rx) = mpsc::channel();
+
+    unwrap!(auth.send(move |client| {
+        let client = client.clone();
+
+        let _ = tokio::spawn(async move {
+            let result = client.resume_transfer_actor().await;
+            unwrap!(tx.send(result));
+        });
+
+        ok!(()).into()
+    }));
+
+    unwrap!(unwrap!(rx.recv()));
+
+    let (tx, rx) = mpsc::channel();
+
+    unwrap!(auth.send(move |client| {
+        client
+            .get_balance(None)
+            .map(move |balance| unwrap!(tx.send(balance)))
+            .map_err(|e| println!("Error fetching balance: {:?}", e))
+            .into_box()
+            .into()
+    }));
+
+    let initial_balance = unwrap!(rx.recv());
+
+    println!("Initial balance: {}", initial_balance);
+
     for _ in immutable_data_count..(immutable_data_count + mutable_data_count) {
         // Construct data
-        let mutable_data = random_mutable_data(100_000, &public_key, &mut rng);
-        stored_data.push(Data::Mutable(mutable_data));
+        let data = random_mutable_data(100_000, &public_key, &mut rng);
+        let (inserts, follow_up, expected_entries, deleted_keys) =
+            random_mdata_mutations(mutations_per_mdata, &mut rng);
+        stored_data.push(Data::Mutable {
+            data,
+            inserts,
+            follow_up,
+            expected_entries,
+            deleted_keys,
+        });
+    }
+
+    for _ in 0..sequence_count {
+        stored_data.push(random_sequences(sequence_entries, &public_key, &mut rng));
     }
 
     let message = format!(
-        "Generated {} items ({} immutable, {} mutable)",
+        "Generated {} items ({} immutable, {} mutable, {} sequence pairs)",
         stored_data.len(),
         immutable_data_count,
-        mutable_data_count
+        mutable_data_count,
+        sequence_count
     );
     let underline = (0..message.len()).map(|_| "=").collect::<String>();
 
     println!("\n\t{}\n\t{}", message, underline);
+    println!("Concurrency: {}", concurrency);
+
+    let (tx, rx) = mpsc::channel();
+    let mut in_flight = 0;
+    let mut timings = Timings::default();
+    let run_started = Instant::now();
+    let stop_requested = Arc::new(AtomicBool::new(false));
 
     for (i, data) in stored_data.into_iter().enumerate() {
-        let (tx, rx) = mpsc::channel();
+        if stop_requested.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if in_flight >= concurrency {
+            let (latency, cost) = unwrap!(rx.recv());
+            timings.record(latency, cost);
+            in_flight -= 1;
+        }
+
+        if stop_requested.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let tx = tx.clone();
+        let stop_requested = stop_requested.clone();
+        let item_started = Instant::now();
 
         unwrap!(auth.send(move |client| {
             let c2 = client.clone();
             let c3 = client.clone();
+            let c4 = client.clone();
+            let c5 = client.clone();
+            let c6 = client.clone();
+            let c7 = client.clone();
 
             match data {
                 Data::Immutable(data) => {
@@ -221,12 +508,20 @@ fn main() {
                         c3.get_idata(*data.address()).map(move |retrieved_data| {
                             println!("Retrieved chunk #{}: {:?}", i, data.name());
                             assert_eq!(data, retrieved_data);
-                            Ok(())
+                            // Immutable data is put through the legacy, un-metered API, so
+                            // there's no payment proof to account for.
+                            Ok(None)
                         })
                     })
                     .into_box()
                 }
-                Data::Mutable(data) => {
+                Data::Mutable {
+                    data,
+                    inserts,
+                    follow_up,
+                    expected_entries,
+                    deleted_keys,
+                } => {
                     let fut = if get_only {
                         futures::finished(data).into_box()
                     } else {
@@ -234,32 +529,160 @@ fn main() {
                         put_mdata(&c2, data, i)
                     };
 
-                    // TODO(nbaksalyar): stress test mutate_mdata and get_mdata_value here
                     fut.and_then(move |data| {
                         // Get all the chunks again.
                         c3.get_seq_mdata_shell(*data.name(), data.tag()).map(
                             move |retrieved_data| {
                                 assert_eq!(data, retrieved_data);
                                 println!("Retrieved chunk #{}: {:?}", i, data.name());
-                                Ok(())
+                                data
                             },
                         )
                     })
+                    .and_then(move |data| {
+                        let name = *data.name();
+                        let tag = data.tag();
+
+                        let mutate_fut = if get_only {
+                            futures::finished(()).into_box()
+                        } else {
+                            // `MDataSeqEntryActions` can only carry one action per key, so
+                            // "insert then update/delete" has to be two sequential batches:
+                            // insert the keys first, then mutate the now-existing entries.
+                            c4.mutate_seq_mdata_entries(name, tag, inserts)
+                                .and_then(move |_| {
+                                    c7.mutate_seq_mdata_entries(name, tag, follow_up)
+                                })
+                                .into_box()
+                        };
+
+                        mutate_fut.and_then(move |_| {
+                            println!("Mutated MutableData chunk #{}: {:?}", i, name);
+
+                            // Read every surviving key back individually and check it matches
+                            // the locally-computed expected state.
+                            let reads =
+                                expected_entries
+                                    .into_iter()
+                                    .map(move |(key, expected_value)| {
+                                        let c5 = c5.clone();
+                                        c5.get_seq_mdata_value(name, tag, key).map(move |value| {
+                                            assert_eq!(value.data, expected_value);
+                                        })
+                                    });
+
+                            // Check that every deleted key is actually gone.
+                            let deletion_checks = deleted_keys.into_iter().map(move |key| {
+                                let c6 = c6.clone();
+                                c6.get_seq_mdata_value(name, tag, key)
+                                    .then(|res| match res {
+                                        Err(CoreError::DataError(SndError::NoSuchEntry)) => Ok(()),
+                                        Ok(_) => {
+                                            panic!("Deleted mdata key unexpectedly still present")
+                                        }
+                                        Err(e) => Err(e),
+                                    })
+                            });
+
+                            futures::future::join_all(reads)
+                                .join(futures::future::join_all(deletion_checks))
+                                .map(move |_| {
+                                    println!("Verified mutations for chunk #{}: {:?}", i, name);
+                                    // Mutable data is put through the legacy, un-metered API,
+                                    // so there's no payment proof to account for.
+                                    Ok(None)
+                                })
+                        })
+                    })
+                    .into_box()
+                }
+                Data::Sequences {
+                    public,
+                    private,
+                    expected_entries,
+                } => {
+                    let fut = if get_only {
+                        futures::finished((public, private, None)).into_box()
+                    } else {
+                        // Put the data to the network.
+                        put_sequence(&c2, public, private, i)
+                    };
+
+                    fut.and_then(move |(public, private, cost)| {
+                        // Get both sequences back and check that every appended entry
+                        // round-tripped in order.
+                        c3.get_sequence(*public.address())
+                            .join(c3.get_sequence(*private.address()))
+                            .map(move |(public_retrieved, private_retrieved)| {
+                                assert_eq!(unwrap!(public_retrieved.entries()), expected_entries);
+                                assert_eq!(unwrap!(private_retrieved.entries()), expected_entries);
+                                println!("Retrieved sequence pair #{}: {:?}", i, public.name());
+                                Ok(cost)
+                            })
+                    })
                     .into_box()
                 }
             }
-            .map(move |_: Result<(), CoreError>| unwrap!(tx.send(())))
-            .map_err(|e| println!("Error: {:?}", e))
+            .then(move |result: Result<Result<Option<Money>, CoreError>, CoreError>| {
+                // Exactly one completion tuple must reach `tx` for every dispatched item,
+                // whichever way it resolves - the throttle wait and the final drain loop
+                // both call `rx.recv()` once per item, so a silently-dropped failure would
+                // block one of those calls forever.
+                match result {
+                    Ok(cost) => unwrap!(tx.send((item_started.elapsed(), unwrap!(cost)))),
+                    Err(e) => {
+                        if fail_on_insufficient_balance && is_insufficient_balance(&e) {
+                            println!(
+                                "Insufficient balance to store further data (item #{}) - \
+                                 stopping submissions, summary follows once in-flight items finish.",
+                                i
+                            );
+                            stop_requested.store(true, Ordering::SeqCst);
+                        } else {
+                            println!("Error: {:?}", e);
+                        }
+                        unwrap!(tx.send((item_started.elapsed(), None)));
+                    }
+                }
+                futures::finished::<(), ()>(())
+            })
             .into_box()
             .into()
         }));
 
-        unwrap!(rx.recv());
+        in_flight += 1;
+    }
+
+    // Drain the remaining in-flight requests, including any that raced with a stop request
+    // above but were already dispatched.
+    for _ in 0..in_flight {
+        let (latency, cost) = unwrap!(rx.recv());
+        timings.record(latency, cost);
     }
 
+    let (tx, rx) = mpsc::channel();
+
+    unwrap!(auth.send(move |client| {
+        client
+            .get_balance(None)
+            .map(move |balance| unwrap!(tx.send(balance)))
+            .map_err(|e| println!("Error fetching balance: {:?}", e))
+            .into_box()
+            .into()
+    }));
+
+    let final_balance = rx.recv().ok();
+
+    timings.print_summary(run_started.elapsed(), final_balance);
+
     println!("Done");
 }
 
+/// Whether `error` reflects the account having run out of money to pay for further mutations.
+fn is_insufficient_balance(error: &CoreError) -> bool {
+    matches!(error, CoreError::DataError(SndError::InsufficientBalance))
+}
+
 fn put_idata(client: &AuthClient, data: IData, i: usize) -> Box<CoreFuture<IData>> {
     let c2 = client.clone();
 
@@ -314,3 +737,35 @@ fn put_mdata(
         })
         .into_box()
 }
+
+fn put_sequence(
+    client: &AuthClient,
+    public: Sequence,
+    private: Sequence,
+    i: usize,
+) -> Box<CoreFuture<(Sequence, Sequence, Option<Money>)>> {
+    let c2 = client.clone();
+
+    client
+        .pay_and_write_sequence_to_network(public.clone())
+        .join(c2.pay_and_write_sequence_to_network(private.clone()))
+        .and_then(
+            move |(public_proof, private_proof): (
+                TransferAgreementProof,
+                TransferAgreementProof,
+            )| {
+                let cost = public_proof
+                    .amount()
+                    .checked_add(private_proof.amount())
+                    .unwrap_or_else(Money::zero);
+                println!(
+                    "Put Sequence pair #{}: {:?} (cost: {})",
+                    i,
+                    public.name(),
+                    cost
+                );
+                Ok((public, private, Some(cost)))
+            },
+        )
+        .into_box()
+}
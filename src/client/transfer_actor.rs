@@ -0,0 +1,2 @@
+mod persistence;
+mod write_apis;
@@ -0,0 +1,263 @@
+use sn_data_types::PublicKey;
+use sn_transfers::ActorEvent;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::sync::{Mutex as AsyncMutex, OnceCell};
+
+use crate::client::Client;
+use crate::errors::Error;
+
+/// Guards the on-disk event log's read-modify-write cycle, independently of any individual
+/// client's in-memory actor lock. This is deliberately a single process-wide lock rather than
+/// one per public key: the log append itself is fast, and keeping it separate from the actor
+/// lock is what matters - it lets concurrent debits (e.g. a stress run under
+/// `--concurrency N > 1`) overlap their network round trips instead of serializing behind this
+/// disk I/O.
+static EVENT_LOG_WRITE_LOCK: OnceCell<AsyncMutex<()>> = OnceCell::const_new();
+
+async fn event_log_write_lock() -> &'static AsyncMutex<()> {
+    EVENT_LOG_WRITE_LOCK
+        .get_or_init(|| async { AsyncMutex::new(()) })
+        .await
+}
+
+/// Directory (under the OS temp dir) where per-client transfer actor event logs are kept, so a
+/// restarted client can reconcile locally-applied-but-unconfirmed debits against the network
+/// instead of double-spending or losing track of pending registrations.
+///
+/// This uses `std::env::temp_dir()` rather than a platform "local data dir" crate: this tree
+/// has no `Cargo.toml` to declare such a dependency against, and the standard library already
+/// gives us a writable, per-OS-appropriate directory without one.
+const TRANSFER_ACTOR_EVENT_LOG_DIR: &str = "safe_client_libs/transfer_actor_events";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn event_log_path(public_key: &PublicKey) -> Result<PathBuf, Error> {
+    let mut path = std::env::temp_dir();
+    path.push(TRANSFER_ACTOR_EVENT_LOG_DIR);
+    fs::create_dir_all(&path)?;
+    path.push(format!("{}.json", to_hex(&public_key.to_bytes())));
+    Ok(path)
+}
+
+fn read_events(public_key: &PublicKey) -> Result<Vec<ActorEvent>, Error> {
+    let path = event_log_path(public_key)?;
+
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    if contents.is_empty() {
+        return Ok(vec![]);
+    }
+
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+fn write_events(public_key: &PublicKey, events: &[ActorEvent]) -> Result<(), Error> {
+    let path = event_log_path(public_key)?;
+    let serialized = serde_json::to_vec(events)?;
+
+    // Write to a temp file in the same directory first and rename it into place, so a crash
+    // mid-write can never leave behind a truncated log for `read_events` to choke on - the
+    // rename is atomic, so the target always reflects either the previous write or this one.
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serialized)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+impl Client {
+    /// Append a just-applied actor event to this client's local event log, so it survives a
+    /// crash or restart mid-run.
+    pub(crate) async fn persist_transfer_actor_event(
+        &self,
+        event: ActorEvent,
+    ) -> Result<(), Error> {
+        let public_key = self.public_key();
+
+        let _write_guard = event_log_write_lock().await.lock().await;
+
+        let mut events = read_events(&public_key)?;
+        events.push(event);
+        write_events(&public_key, &events)
+    }
+
+    /// Rebuild the transfer actor by replaying this client's saved event log, reconciling any
+    /// locally-applied-but-unconfirmed debits against the network instead of double-spending or
+    /// losing track of pending registrations.
+    ///
+    /// The persisted log is compacted as events are successfully applied, not just once the
+    /// whole replay succeeds: if `actor.apply` fails partway through (e.g. on a later event),
+    /// the already-applied prefix is dropped from disk first, so a subsequent resume attempt
+    /// only replays the unapplied remainder instead of re-`apply`-ing (and double-counting)
+    /// events the actor already has. This also bounds the log across process restarts to
+    /// whatever a single run accumulates, rather than letting it grow forever.
+    ///
+    /// Note this module has no way to observe which events the network has since confirmed, so
+    /// within a single run the log still grows by one entry per `persist_transfer_actor_event`
+    /// call until the process restarts (or this method runs again). For the stress-test tool
+    /// this is bounded by the run's configured item count; a longer-lived client would need a
+    /// network-confirmation signal plumbed in before it could safely compact mid-run.
+    pub async fn resume_transfer_actor(&self) -> Result<(), Error> {
+        let public_key = self.public_key();
+
+        let _write_guard = event_log_write_lock().await.lock().await;
+        let events = read_events(&public_key)?;
+
+        let mut actor = self.transfer_actor.lock().await;
+        for (applied, event) in events.iter().enumerate() {
+            if let Err(e) = actor.apply(event.clone()) {
+                write_events(&public_key, &events[applied..])?;
+                return Err(e.into());
+            }
+            write_events(&public_key, &events[applied + 1..])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::create_test_client_with;
+    use anyhow::Result;
+    use rand::rngs::OsRng;
+    use sn_data_types::{Keypair, Sequence};
+    use xor_name::XorName;
+
+    fn random_public_key() -> PublicKey {
+        Keypair::new_ed25519(&mut OsRng).public_key()
+    }
+
+    #[test]
+    fn to_hex_is_stable_and_distinguishes_inputs() {
+        assert_eq!(to_hex(&[0x0a, 0xbc]), "0abc");
+        assert_ne!(to_hex(&[0x01]), to_hex(&[0x02]));
+    }
+
+    #[test]
+    fn event_log_path_is_stable_per_key_and_differs_across_keys() -> Result<()> {
+        let key_a = random_public_key();
+        let key_b = random_public_key();
+
+        assert_eq!(event_log_path(&key_a)?, event_log_path(&key_a)?);
+        assert_ne!(event_log_path(&key_a)?, event_log_path(&key_b)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_then_read_events_round_trips_and_cleans_up_the_temp_file() -> Result<()> {
+        let public_key = random_public_key();
+
+        // No log on disk yet: reads back as an empty log rather than erroring.
+        assert_eq!(read_events(&public_key)?, vec![]);
+
+        write_events(&public_key, &[])?;
+        assert_eq!(read_events(&public_key)?, vec![]);
+
+        // The atomic write should leave no stray `.json.tmp` file behind.
+        let tmp_path = event_log_path(&public_key)?.with_extension("json.tmp");
+        assert!(!tmp_path.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_transfer_actor_is_a_no_op_with_no_persisted_log() -> Result<()> {
+        let keypair = Keypair::new_ed25519(&mut OsRng);
+        let client = create_test_client_with(Some(keypair)).await?;
+
+        // A freshly created client has nothing persisted yet, so resuming must succeed
+        // without replaying (or failing on) any events.
+        client.resume_transfer_actor().await?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "simulated-payouts")]
+    #[tokio::test]
+    async fn resume_transfer_actor_replays_persisted_events_into_a_fresh_actor() -> Result<()> {
+        let keypair = Keypair::new_ed25519(&mut OsRng);
+        let authority = keypair.public_key();
+        let data = Sequence::new_public(
+            authority,
+            authority.to_string(),
+            XorName::random(),
+            1,
+            None,
+        );
+
+        let client = create_test_client_with(Some(keypair.clone())).await?;
+        let _ = client.pay_and_write_sequence_to_network(data).await?;
+
+        let persisted = read_events(&authority)?;
+        assert_eq!(
+            persisted.len(),
+            1,
+            "the payment above should have persisted one event"
+        );
+
+        // Model a restarted process: a fresh `Client` for the same identity has an in-memory
+        // actor that has not seen the debit above.
+        let restarted = create_test_client_with(Some(keypair)).await?;
+        restarted.resume_transfer_actor().await?;
+
+        // The replayed event is now part of the actor's state, so re-applying it is rejected as
+        // a duplicate instead of being silently double-counted.
+        {
+            let mut actor = restarted.transfer_actor.lock().await;
+            assert!(actor.apply(persisted[0].clone()).is_err());
+        }
+
+        // And the log was compacted once fully replayed.
+        assert_eq!(read_events(&authority)?, vec![]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "simulated-payouts")]
+    #[tokio::test]
+    async fn resume_transfer_actor_compacts_only_the_applied_prefix_on_partial_failure(
+    ) -> Result<()> {
+        let keypair = Keypair::new_ed25519(&mut OsRng);
+        let authority = keypair.public_key();
+        let data = Sequence::new_public(
+            authority,
+            authority.to_string(),
+            XorName::random(),
+            1,
+            None,
+        );
+
+        // Produce one real, already-applicable event for this identity via an actual payment.
+        let client = create_test_client_with(Some(keypair.clone())).await?;
+        let _ = client.pay_and_write_sequence_to_network(data).await?;
+        let event = read_events(&authority)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("payment above should have persisted an event"))?;
+
+        // Write a log with the event followed by a stale duplicate of itself - as could happen
+        // if a previous resume crashed after applying an event but before compacting it off
+        // disk. Replay against a fresh in-memory actor for the same identity: the first copy
+        // applies fine, the second fails as an already-seen duplicate.
+        write_events(&authority, &[event.clone(), event])?;
+
+        let restarted = create_test_client_with(Some(keypair)).await?;
+        assert!(restarted.resume_transfer_actor().await.is_err());
+
+        // Only the unapplied (duplicate, failing) suffix remains - the successfully-applied
+        // first copy was compacted off disk before the second copy's failure was returned.
+        assert_eq!(read_events(&authority)?.len(), 1);
+
+        Ok(())
+    }
+}
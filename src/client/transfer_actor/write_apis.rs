@@ -11,13 +11,27 @@ impl Client {
         &self,
         debit_proof: TransferAgreementProof,
     ) -> Result<(), Error> {
-        let mut actor = self.transfer_actor.lock().await;
-        // First register with local actor, then reply.
-        let register_event = actor
-            .register(debit_proof.clone())?
-            .ok_or(Error::NoTransferEventsForLocalActor)?;
+        let event = {
+            let mut actor = self.transfer_actor.lock().await;
+            // First register with local actor, then reply.
+            let register_event = actor
+                .register(debit_proof.clone())?
+                .ok_or(Error::NoTransferEventsForLocalActor)?;
 
-        actor.apply(ActorEvent::TransferRegistrationSent(register_event))?;
+            let event = ActorEvent::TransferRegistrationSent(register_event);
+            actor.apply(event.clone())?;
+            event
+        };
+
+        // Persist the applied event so a restarted client can replay it via
+        // `resume_transfer_actor` instead of losing track of this debit. This runs after the
+        // actor lock above is released, so concurrent debits (e.g. from a stress run with
+        // `--concurrency N > 1`) don't serialize their network round trips behind this disk
+        // I/O - only the log append itself is serialized (see `persist_transfer_actor_event`).
+        // The log may therefore record concurrent events in a different order than they were
+        // applied in memory; `resume_transfer_actor` replays them in log order, not in the order
+        // they were originally applied.
+        self.persist_transfer_actor_event(event).await?;
 
         Ok(())
     }